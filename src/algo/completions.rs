@@ -0,0 +1,183 @@
+use super::super::CmdRunner;
+use docopt::Docopt;
+
+use std::vec::IntoIter;
+use algorithmia::Algorithmia;
+
+static USAGE: &'static str = "Usage:
+  algo completions <shell>
+
+  Emit a shell completion script for the CLI to STDOUT.
+
+  <shell> is one of: bash, zsh, fish
+
+  Examples:
+    algo completions zsh > _algo            Generate zsh completions
+    algo completions bash > algo.bash       Generate bash completions
+";
+
+#[derive(RustcDecodable, Debug)]
+struct Args {
+    arg_shell: String,
+}
+
+// A CLI flag worth completing. `file` marks options whose argument is a path, so
+// the generated scripts fall back to file-path completion after them.
+struct Flag {
+    short: &'static str,
+    long: &'static str,
+    takes_value: bool,
+    file: bool,
+}
+
+// Derived from the `run` USAGE. Docopt is the source of truth for parsing, so this
+// table is kept deliberately small and only drives tab-completion.
+static RUN_FLAGS: &'static [Flag] = &[
+    Flag { short: "-d", long: "--data", takes_value: true, file: false },
+    Flag { short: "-D", long: "--data-file", takes_value: true, file: true },
+    Flag { short: "-j", long: "--json", takes_value: true, file: false },
+    Flag { short: "-J", long: "--json-file", takes_value: true, file: true },
+    Flag { short: "-t", long: "--text", takes_value: true, file: false },
+    Flag { short: "-T", long: "--text-file", takes_value: true, file: true },
+    Flag { short: "-b", long: "--binary", takes_value: true, file: false },
+    Flag { short: "-B", long: "--binary-file", takes_value: true, file: true },
+    Flag { short: "-o", long: "--output", takes_value: true, file: true },
+    Flag { short: "-w", long: "--write", takes_value: true, file: true },
+    Flag { short: "-r", long: "--read", takes_value: true, file: true },
+    Flag { short: "-s", long: "--silence", takes_value: false, file: false },
+    Flag { short: "-m", long: "--meta", takes_value: false, file: false },
+    Flag { short: "", long: "--debug", takes_value: false, file: false },
+    Flag { short: "", long: "--response", takes_value: false, file: false },
+    Flag { short: "", long: "--response-body", takes_value: false, file: false },
+    Flag { short: "", long: "--timeout", takes_value: true, file: false },
+    Flag { short: "", long: "--concurrency", takes_value: true, file: false },
+    Flag { short: "", long: "--json-errors", takes_value: false, file: false },
+];
+
+static SUBCOMMANDS: &'static [&'static str] = &["run", "completions"];
+
+pub struct Completions;
+impl CmdRunner for Completions {
+    fn get_usage() -> &'static str { USAGE }
+
+    fn cmd_main(&self, argv: IntoIter<String>) {
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(argv).decode())
+            .unwrap_or_else(|e| e.exit());
+
+        match &*args.arg_shell {
+            "bash" => print!("{}", bash_completions()),
+            "zsh" => print!("{}", zsh_completions()),
+            "fish" => print!("{}", fish_completions()),
+            other => die!("Unsupported shell '{}' (expected bash, zsh, or fish)", other),
+        }
+    }
+}
+
+impl Completions {
+    // Completions are generated from static tables and need no API client, but the
+    // constructor mirrors the other subcommands' `new(client)` dispatch signature.
+    pub fn new(_client: Algorithmia) -> Self { Completions }
+}
+
+// Every flag spelling (short and long) joined into a space-separated word list.
+fn run_flag_words() -> String {
+    let mut words: Vec<&str> = Vec::new();
+    for flag in RUN_FLAGS {
+        if !flag.short.is_empty() { words.push(flag.short); }
+        words.push(flag.long);
+    }
+    words.join(" ")
+}
+
+fn bash_completions() -> String {
+    let subcommands = SUBCOMMANDS.join(" ");
+    let flags = run_flag_words();
+
+    // Options whose argument is a path get file completion.
+    let mut file_opts: Vec<&str> = Vec::new();
+    for flag in RUN_FLAGS {
+        if flag.file {
+            if !flag.short.is_empty() { file_opts.push(flag.short); }
+            file_opts.push(flag.long);
+        }
+    }
+    let file_case = file_opts.join("|");
+
+    format!("_algo() {{
+    local cur prev
+    COMPREPLY=()
+    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"
+    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"
+
+    case \"$prev\" in
+        {file_case})
+            COMPREPLY=( $(compgen -f -- \"$cur\") )
+            return 0
+            ;;
+    esac
+
+    if [ \"$COMP_CWORD\" -eq 1 ]; then
+        COMPREPLY=( $(compgen -W \"{subcommands}\" -- \"$cur\") )
+        return 0
+    fi
+
+    COMPREPLY=( $(compgen -W \"{flags}\" -- \"$cur\") )
+    return 0
+}}
+complete -F _algo algo
+", file_case = file_case, subcommands = subcommands, flags = flags)
+}
+
+fn zsh_completions() -> String {
+    // Build a `_arguments` spec line per flag, with `:file:_files` for path options.
+    let mut specs: Vec<String> = Vec::new();
+    for flag in RUN_FLAGS {
+        let action = if flag.file {
+            ":file:_files"
+        } else if flag.takes_value {
+            ":value:"
+        } else {
+            ""
+        };
+        if !flag.short.is_empty() {
+            specs.push(format!("    '{}[{}]{}'", flag.short, flag.long, action));
+        }
+        specs.push(format!("    '{}[{}]{}'", flag.long, flag.long, action));
+    }
+    let specs = specs.join(" \\\n");
+    let subcommands = SUBCOMMANDS.join(" ");
+
+    format!("#compdef algo
+
+_algo() {{
+    local curcontext=\"$curcontext\" state line
+    if (( CURRENT == 2 )); then
+        compadd {subcommands}
+        return
+    fi
+    _arguments \\
+{specs}
+}}
+
+_algo \"$@\"
+", subcommands = subcommands, specs = specs)
+}
+
+fn fish_completions() -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for sub in SUBCOMMANDS {
+        lines.push(format!("complete -c algo -n '__fish_use_subcommand' -a {}", sub));
+    }
+    for flag in RUN_FLAGS {
+        let mut line = String::from("complete -c algo");
+        if !flag.short.is_empty() {
+            line.push_str(&format!(" -s {}", flag.short.trim_left_matches('-')));
+        }
+        line.push_str(&format!(" -l {}", flag.long.trim_left_matches('-')));
+        if flag.takes_value { line.push_str(" -r"); }
+        if flag.file { line.push_str(" -F"); }
+        lines.push(line);
+    }
+    format!("{}\n", lines.join("\n"))
+}
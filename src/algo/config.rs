@@ -0,0 +1,243 @@
+use super::run::strip_jsonc;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use glob::Pattern;
+use rustc_serialize::json::Json;
+
+// A commented default that is written out the first time the CLI runs without a
+// config, so users have something to edit (mirrors ripgrep-all's behavior).
+static DEFAULT_CONFIG: &'static str = "{
+  // Algorithmia CLI configuration. Command-line flags always override these.
+
+  // Default algorithm timeout in seconds.
+  // \"timeout\": 300,
+
+  // Default output format: \"result\", \"response\", or \"response-body\".
+  // \"output\": \"result\",
+
+  // Suppress non-result notices (equivalent to always passing --silence).
+  // \"silence\": false,
+
+  // Per-algorithm defaults keyed by USERNAME/ALGONAME.
+  \"algorithms\": {
+    // \"demo/Hello\": { \"version\": \"0.1.0\", \"input_type\": \"text\" }
+  },
+
+  // External preprocessing adapters, tried in order against each -D/--data-file.
+  // The file is streamed to the command's STDIN and its STDOUT becomes the input.
+  \"adapters\": [
+    // { \"pattern\": \".csv\", \"command\": \"csv2json\", \"input_type\": \"json\" },
+    // { \"pattern\": \"*.tiff\", \"command\": \"convert - png:-\", \"input_type\": \"binary\" }
+  ]
+}
+";
+
+// Per-algorithm defaults pulled from the `algorithms` section.
+#[derive(Clone, Debug, Default)]
+pub struct AlgoConfig {
+    pub version: Option<String>,
+    pub input_type: Option<String>,
+}
+
+// An external command that preprocesses a matching input file before upload.
+#[derive(Clone, Debug)]
+pub struct Adapter {
+    pub pattern: String,
+    pub command: String,
+    pub input_type: String,
+}
+
+// Defaults for the `run` command sourced from the user config file.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub timeout: Option<u32>,
+    pub output: Option<String>,
+    pub silence: Option<bool>,
+    algorithms: BTreeMap<String, AlgoConfig>,
+    adapters: Vec<Adapter>,
+}
+
+impl Config {
+    // Load the user config, regenerating a commented default if none exists.
+    // A malformed or invalid config is fatal since it usually signals a typo the
+    // user wants to know about rather than silently ignore.
+    pub fn load() -> Config {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return Config::default(),
+        };
+
+        if !path.exists() {
+            write_default(&path);
+            return Config::default();
+        }
+
+        let mut raw = String::new();
+        match File::open(&path).and_then(|mut f| f.read_to_string(&mut raw)) {
+            Ok(_) => {},
+            Err(err) => die!("Error reading config {}: {}", path.display(), err),
+        }
+
+        let json = match Json::from_str(&strip_jsonc(&raw)) {
+            Ok(json) => json,
+            Err(err) => die!("Invalid config {}: {}", path.display(), err),
+        };
+
+        match Config::from_json(&json) {
+            Ok(config) => config,
+            Err(err) => die!("Invalid config {}: {}", path.display(), err),
+        }
+    }
+
+    // Resolved defaults for a specific algorithm, keyed by USERNAME/ALGONAME
+    // (any /VERSION suffix is ignored for the lookup).
+    pub fn algorithm(&self, algorithm: &str) -> AlgoConfig {
+        let key: String = algorithm.splitn(3, '/').take(2).collect::<Vec<_>>().join("/");
+        self.algorithms.get(&key).cloned().unwrap_or_default()
+    }
+
+    // The first adapter whose pattern matches the given input path, if any. A
+    // pattern beginning with `.` matches by file extension; anything else is
+    // treated as a glob against the file name.
+    pub fn adapter_for(&self, path: &str) -> Option<&Adapter> {
+        let name = Path::new(path).file_name()
+            .map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let ext = Path::new(path).extension()
+            .map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        self.adapters.iter().find(|adapter| {
+            if adapter.pattern.starts_with('.') {
+                format!(".{}", ext) == adapter.pattern
+            } else {
+                Pattern::new(&adapter.pattern).map(|p| p.matches(&name)).unwrap_or(false)
+            }
+        })
+    }
+
+    fn from_json(json: &Json) -> Result<Config, String> {
+        let obj = try!(json.as_object().ok_or_else(|| "expected a JSON object".to_owned()));
+        let mut config = Config::default();
+
+        for (key, value) in obj {
+            match &**key {
+                "timeout" => config.timeout = Some(try!(as_u32(value, "timeout"))),
+                "silence" => config.silence = Some(try!(value.as_boolean()
+                    .ok_or_else(|| "`silence` must be a boolean".to_owned()))),
+                "output" => {
+                    let format = try!(value.as_string()
+                        .ok_or_else(|| "`output` must be a string".to_owned()));
+                    match format {
+                        "result" | "response" | "response-body" => config.output = Some(format.to_owned()),
+                        other => return Err(format!("unknown `output` value '{}'", other)),
+                    }
+                },
+                "algorithms" => {
+                    let algos = try!(value.as_object()
+                        .ok_or_else(|| "`algorithms` must be an object".to_owned()));
+                    for (name, entry) in algos {
+                        config.algorithms.insert(name.clone(), try!(algo_from_json(name, entry)));
+                    }
+                },
+                "adapters" => {
+                    let adapters = try!(value.as_array()
+                        .ok_or_else(|| "`adapters` must be an array".to_owned()));
+                    for entry in adapters {
+                        config.adapters.push(try!(adapter_from_json(entry)));
+                    }
+                },
+                other => return Err(format!("unknown config key '{}'", other)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn algo_from_json(name: &str, json: &Json) -> Result<AlgoConfig, String> {
+    let obj = try!(json.as_object()
+        .ok_or_else(|| format!("`algorithms.{}` must be an object", name)));
+    let mut algo = AlgoConfig::default();
+
+    for (key, value) in obj {
+        match &**key {
+            "version" => algo.version = Some(try!(value.as_string()
+                .ok_or_else(|| format!("`algorithms.{}.version` must be a string", name)))
+                .to_owned()),
+            "input_type" => {
+                let kind = try!(value.as_string()
+                    .ok_or_else(|| format!("`algorithms.{}.input_type` must be a string", name)));
+                match kind {
+                    "auto" | "json" | "text" | "binary" => algo.input_type = Some(kind.to_owned()),
+                    other => return Err(format!("unknown input_type '{}' for {}", other, name)),
+                }
+            },
+            other => return Err(format!("unknown key '{}' in algorithms.{}", other, name)),
+        }
+    }
+
+    Ok(algo)
+}
+
+fn adapter_from_json(json: &Json) -> Result<Adapter, String> {
+    let obj = try!(json.as_object().ok_or_else(|| "each adapter must be an object".to_owned()));
+    let pattern = try!(obj.get("pattern").and_then(|v| v.as_string())
+        .ok_or_else(|| "adapter is missing a string `pattern`".to_owned())).to_owned();
+    let command = try!(obj.get("command").and_then(|v| v.as_string())
+        .ok_or_else(|| format!("adapter '{}' is missing a string `command`", pattern))).to_owned();
+
+    let input_type = match obj.get("input_type") {
+        Some(value) => {
+            let kind = try!(value.as_string()
+                .ok_or_else(|| format!("adapter '{}' input_type must be a string", pattern)));
+            match kind {
+                "auto" | "json" | "text" | "binary" => kind.to_owned(),
+                other => return Err(format!("unknown input_type '{}' for adapter '{}'", other, pattern)),
+            }
+        },
+        None => "auto".to_owned(),
+    };
+
+    for key in obj.keys() {
+        match &**key {
+            "pattern" | "command" | "input_type" => {},
+            other => return Err(format!("unknown key '{}' in adapter '{}'", other, pattern)),
+        }
+    }
+
+    Ok(Adapter { pattern: pattern, command: command, input_type: input_type })
+}
+
+fn as_u32(value: &Json, field: &str) -> Result<u32, String> {
+    match value.as_u64() {
+        Some(n) => Ok(n as u32),
+        None => Err(format!("`{}` must be a non-negative integer", field)),
+    }
+}
+
+// ~/.config/algorithmia/config.jsonc, honoring $XDG_CONFIG_HOME.
+fn config_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => match env::home_dir() {
+            Some(home) => home.join(".config"),
+            None => return None,
+        },
+    };
+    Some(base.join("algorithmia").join("config.jsonc"))
+}
+
+fn write_default(path: &PathBuf) {
+    if let Some(parent) = path.parent() {
+        if let Err(err) = fs::create_dir_all(parent) {
+            stderrln!("Unable to create config dir {}: {}", parent.display(), err);
+            return;
+        }
+    }
+    match File::create(path).and_then(|mut f| f.write_all(DEFAULT_CONFIG.as_bytes())) {
+        Ok(_) => stderrln!("Wrote default config to {}", path.display()),
+        Err(err) => stderrln!("Unable to write default config {}: {}", path.display(), err),
+    }
+}
@@ -1,11 +1,17 @@
 use super::super::CmdRunner;
+use super::config::{Config, AlgoConfig, Adapter};
 use docopt::Docopt;
 
 use std::io::{self, Read, Write};
 use std::fs::File;
 use std::path::Path;
+use std::process::{self, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use std::vec::IntoIter;
-use rustc_serialize::json::Json;
+use glob::glob;
+use rustc_serialize::json::{self, Json};
 use algorithmia::Algorithmia;
 use algorithmia::algo::{AlgoResponse, AlgoOutput, AlgoOptions};
 use algorithmia::mime::*;
@@ -13,6 +19,7 @@ use algorithmia::client::Response;
 
 static USAGE: &'static str = "Usage:
   algo run [options] <algorithm>
+  algo run [options] --read <file>
 
   <algorithm> syntax: USERNAME/ALGONAME[/VERSION]
   Recommend specifying a version since algorithm costs can change between minor versions.
@@ -21,6 +28,10 @@ static USAGE: &'static str = "Usage:
     There are option variants for specifying the type and source of input data.
     If <file> is '-', then input data will be read from STDIN.
 
+    Input options may be repeated to run the algorithm once per input; file options
+    also accept shell-style globs (e.g. -D 'inputs/*.json') that expand to one call
+    per matching file. Use --output with a template to write one result per input.
+
     Auto-Detect Data:
       -d, --data <data>             If the data parses as JSON, assume JSON, else if the data
                                       is valid UTF-8, assume text, else assume binary
@@ -49,16 +60,32 @@ static USAGE: &'static str = "Usage:
     -s, --silence                   Suppress any output not explicitly requested (except result)
     -m, --meta                      Print human-readable selection of metadata (e.g. duration)
     -o, --output <file>             Print result to a file, implies --meta
+                                      When running a batch, <file> may contain the tokens
+                                      {basename}, {filename}, {ext}, and {index} to give each
+                                      input its own output file (e.g. -o 'out/{basename}.json')
+    -w, --write <file>              Save the full response (headers, metadata, result) to a
+                                      file; accepts the same {basename} tokens as --output
+    -r, --read <file>               Re-render a response saved with --write instead of calling
+                                      the algorithm (re-format results without re-billing)
 
 
   Other Options:
     --timeout <seconds>             Sets algorithm timeout
+    --concurrency <N>               Dispatch batch inputs through a pool of N workers [default: 1]
+    --json-errors                   On failure, emit {class, message, algorithm} to STDERR
+                                      instead of a free-text message
+
+  Exit Codes:
+    Failures map to distinct exit codes for scripting: 3 (io), 4 (input-parse),
+    5 (transport), 6 (algorithm-runtime), 7 (response-parse).
 
   Examples:
     algo kenny/factor/0.1.0 -t '79'                   Run algorithm with specified data input
     algo anowell/Dijkstra -J routes.json              Run algorithm with file input
     algo anowell/Dijkstra -J - < routes.json          Same as above but using STDIN
     algo opencv/SmartThumbnail -B in.png -o out.png   Runs algorithm with binary data input
+    algo kenny/factor -D 'nums/*.txt' -o '{basename}.out' --concurrency 4
+                                                      Run once per file, 4 calls in flight
 ";
 
 
@@ -72,7 +99,167 @@ struct Args {
     flag_meta: bool,
     flag_debug: bool,
     flag_output: Option<String>,
+    flag_write: Option<String>,
+    flag_read: Option<String>,
     flag_timeout: Option<u32>,
+    flag_concurrency: Option<usize>,
+    flag_json_errors: bool,
+}
+
+// Failure categories, each mapped to a distinct process exit code so that callers
+// can branch on the kind of failure rather than a single catch-all status.
+#[derive(Clone, Copy)]
+enum ErrorClass {
+    Io,
+    InputParse,
+    Transport,
+    AlgorithmRuntime,
+    ResponseParse,
+}
+
+impl ErrorClass {
+    fn code(&self) -> i32 {
+        match *self {
+            ErrorClass::Io => 3,
+            ErrorClass::InputParse => 4,
+            ErrorClass::Transport => 5,
+            ErrorClass::AlgorithmRuntime => 6,
+            ErrorClass::ResponseParse => 7,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            ErrorClass::Io => "io",
+            ErrorClass::InputParse => "input-parse",
+            ErrorClass::Transport => "transport",
+            ErrorClass::AlgorithmRuntime => "algorithm-runtime",
+            ErrorClass::ResponseParse => "response-parse",
+        }
+    }
+}
+
+// A classified failure carrying the message that would otherwise go to `die!`.
+struct RunError {
+    class: ErrorClass,
+    message: String,
+}
+
+impl RunError {
+    fn new(class: ErrorClass, message: String) -> RunError {
+        RunError { class: class, message: message }
+    }
+}
+
+// The JSON object emitted to STDERR under --json-errors.
+#[derive(RustcEncodable)]
+struct JsonError<'a> {
+    class: &'a str,
+    message: &'a str,
+    algorithm: &'a str,
+}
+
+// A fully parsed response persisted by --write and reconstructed by --read. The
+// `body` is the verbatim JSON the API returned (metadata + result); the other
+// fields preserve enough of the HTTP response to reproduce --response output.
+#[derive(RustcEncodable, RustcDecodable)]
+struct SavedResponse {
+    version: String,
+    status: String,
+    headers: String,
+    body: String,
+}
+
+// The subset of Args needed to run and render a single input. Unlike Args it is
+// Clone + Send so that it can be handed to each worker in the batch thread pool.
+#[derive(Clone)]
+struct RunConfig {
+    algorithm: String,
+    debug: bool,
+    timeout: Option<u32>,
+    silence: bool,
+    meta: bool,
+    response: bool,
+    response_body: bool,
+    output: Option<String>,
+    write: Option<String>,
+    json_errors: bool,
+    // Per-algorithm default input type applied to auto-detected inputs.
+    input_type: Option<String>,
+}
+
+impl RunConfig {
+    fn from_args(args: &Args) -> RunConfig {
+        RunConfig {
+            algorithm: args.arg_algorithm.clone(),
+            debug: args.flag_debug,
+            timeout: args.flag_timeout,
+            silence: args.flag_silence,
+            meta: args.flag_meta,
+            response: args.flag_response,
+            response_body: args.flag_response_body,
+            output: args.flag_output.clone(),
+            write: args.flag_write.clone(),
+            json_errors: args.flag_json_errors,
+            input_type: None,
+        }
+    }
+
+    // Fill in output-rendering defaults from the user config (CLI still wins).
+    fn apply_config(&mut self, user: &Config) {
+        if !self.silence { self.silence = user.silence.unwrap_or(false); }
+        if !self.response && !self.response_body {
+            match user.output.as_ref().map(|s| &**s) {
+                Some("response") => self.response = true,
+                Some("response-body") => self.response_body = true,
+                _ => {},
+            }
+        }
+    }
+}
+
+// How a file-backed input should be interpreted once its bytes are read.
+#[derive(Clone, Copy)]
+enum FileKind {
+    Auto,
+    Json,
+    Text,
+    Binary,
+}
+
+// A not-yet-resolved input: either data already in hand (command-line flags) or
+// a file path whose contents are read lazily by the worker. Reading is deferred
+// so that a missing/unreadable file is classified and reported like any other
+// run failure (mapped to the `io` exit code and honored by --json-errors)
+// instead of short-circuiting through `die!`.
+enum InputSource {
+    Inline(InputData),
+    File { path: String, kind: FileKind },
+}
+
+// A single input paired with the path it came from. `template_path` is the file
+// path for file-backed inputs (used to expand --output templates) and None for
+// STDIN or data passed directly on the command line.
+struct Input {
+    source: InputSource,
+    template_path: Option<String>,
+    // Whether the type was auto-detected (-d/-D), so per-algorithm config may
+    // re-coerce it to a configured default input type.
+    auto: bool,
+}
+
+impl Input {
+    fn inline(data: InputData, auto: bool) -> Input {
+        Input { source: InputSource::Inline(data), template_path: None, auto: auto }
+    }
+
+    fn from_file(src: &str, kind: FileKind, auto: bool) -> Input {
+        let template_path = match src {
+            "-" => None,
+            s => Some(s.to_owned()),
+        };
+        Input { source: InputSource::File { path: src.to_owned(), kind: kind }, template_path: template_path, auto: auto }
+    }
 }
 
 pub struct Run { client: Algorithmia }
@@ -80,8 +267,11 @@ impl CmdRunner for Run {
     fn get_usage() -> &'static str { USAGE }
 
     fn cmd_main(&self, argv: IntoIter<String>) {
+        // Load user config up front so -D/--data-file can consult preprocessing adapters.
+        let user_config = Config::load();
+
         // We need to preprocess input args before giving other args to Docopt
-        let mut input_args: Vec<InputData> = Vec::new();
+        let mut input_args: Vec<Input> = Vec::new();
         let mut other_args: Vec<String> = Vec::new();
 
         let mut argv_mut = argv.collect::<Vec<String>>().into_iter();
@@ -90,14 +280,22 @@ impl CmdRunner for Run {
         };
         while let Some(flag) = argv_mut.next() {
             match &*flag {
-                "-d" | "--data" => input_args.push(InputData::auto(&mut next_arg(&mut argv_mut).as_bytes())),
-                "-j" | "--json" => input_args.push(InputData::Json(next_arg(&mut argv_mut))),
-                "-t" | "--text" => input_args.push(InputData::Text(next_arg(&mut argv_mut))),
-                "-b" | "--binary" => input_args.push(InputData::Binary(next_arg(&mut argv_mut).into_bytes())),
-                "-D" | "--data-file" => input_args.push(InputData::auto(&mut get_src(&next_arg(&mut argv_mut)))),
-                "-J" | "--json-file" => input_args.push(InputData::json(&mut get_src(&next_arg(&mut argv_mut)))),
-                "-T" | "--text-file" => input_args.push(InputData::text(&mut get_src(&next_arg(&mut argv_mut)))),
-                "-B" | "--binary-file" => input_args.push(InputData::binary(&mut get_src(&next_arg(&mut argv_mut)))),
+                "-d" | "--data" => input_args.push(Input::inline(InputData::classify(next_arg(&mut argv_mut).into_bytes()), true)),
+                "-j" | "--json" => input_args.push(Input::inline(InputData::Json(strip_jsonc(&next_arg(&mut argv_mut))), false)),
+                "-t" | "--text" => input_args.push(Input::inline(InputData::Text(next_arg(&mut argv_mut)), false)),
+                "-b" | "--binary" => input_args.push(Input::inline(InputData::Binary(next_arg(&mut argv_mut).into_bytes()), false)),
+                "-D" | "--data-file" => for src in expand_sources(&next_arg(&mut argv_mut)) {
+                    input_args.push(Input::from_file(&src, FileKind::Auto, true));
+                },
+                "-J" | "--json-file" => for src in expand_sources(&next_arg(&mut argv_mut)) {
+                    input_args.push(Input::from_file(&src, FileKind::Json, false));
+                },
+                "-T" | "--text-file" => for src in expand_sources(&next_arg(&mut argv_mut)) {
+                    input_args.push(Input::from_file(&src, FileKind::Text, false));
+                },
+                "-B" | "--binary-file" => for src in expand_sources(&next_arg(&mut argv_mut)) {
+                    input_args.push(Input::from_file(&src, FileKind::Binary, false));
+                },
                 _ => other_args.push(flag)
             };
         };
@@ -107,73 +305,236 @@ impl CmdRunner for Run {
             .and_then(|d| d.argv(other_args).decode())
             .unwrap_or_else(|e| e.exit());
 
+        // Replay mode (--read): re-render a saved response without calling the API.
+        if let Some(ref path) = args.flag_read {
+            let mut config = RunConfig::from_args(&args);
+            config.apply_config(&user_config);
+            if let Err(err) = replay(path, &config) {
+                report_error(&err, &config);
+                process::exit(err.class.code());
+            }
+            return;
+        }
+
         // Validating args and options
         if input_args.len() < 1 {
             return die!("Must specify an input data option\n\n{}", USAGE);
-        } else if input_args.len() > 1 {
-            return die!("Multiple input data sources is currently not supported");
         }
 
-        let mut opts = AlgoOptions::default();
-        if args.flag_debug { opts.enable_stdout(); }
-        if let Some(timeout) = args.flag_timeout { opts.timeout(timeout); }
+        // Merge user config -> CLI flags (CLI always wins).
+        let algo_config = user_config.algorithm(&args.arg_algorithm);
+
+        let mut config = RunConfig::from_args(&args);
+        config.algorithm = resolve_algorithm(&args.arg_algorithm, &algo_config);
+        if config.timeout.is_none() { config.timeout = user_config.timeout; }
+        config.apply_config(&user_config);
+        // The per-algorithm default input type is applied to auto-detected inputs
+        // once their bytes are read (see execute_one).
+        config.input_type = algo_config.input_type;
+
+        let total = input_args.len();
+        let concurrency = args.flag_concurrency.unwrap_or(1);
+
+        // A batch writing to a single fixed path has every input clobber the same
+        // file; warn rather than silently keep only the last result.
+        if total > 1 {
+            warn_single_target("--output", config.output.as_ref());
+            warn_single_target("--write", config.write.as_ref());
+        }
+
+        // Dispatch every input, reporting per-input failures without aborting.
+        let (failures, exit_code) = run_batch(self.client.clone(), config.clone(), user_config, input_args, concurrency);
+        if failures > 0 {
+            if !config.json_errors {
+                stderrln!("{} of {} inputs failed", failures, total);
+            }
+            process::exit(exit_code);
+        }
+    }
+}
+
+// Run each input, optionally spreading the work across a bounded pool of worker
+// threads since algorithm calls are network-bound. Returns the number of inputs
+// that failed along with the exit code of the first failure; individual failures
+// are reported to STDERR but do not abort the batch.
+fn run_batch(client: Algorithmia, config: RunConfig, user_config: Config, inputs: Vec<Input>, concurrency: usize) -> (usize, i32) {
+    let total = inputs.len();
+    let workers = if total == 1 { 1 } else { concurrency.max(1).min(total) };
+
+    let queue: Arc<Mutex<IntoIter<(usize, Input)>>> = Arc::new(Mutex::new(
+        inputs.into_iter().enumerate().collect::<Vec<_>>().into_iter()));
+    let failures = Arc::new(AtomicUsize::new(0));
+    let exit_code = Arc::new(Mutex::new(0i32));
+    // Serializes writes to the shared terminal so concurrent workers can't
+    // interleave each other's result/metadata output.
+    let stdout_lock = Arc::new(Mutex::new(()));
+    let user_config = Arc::new(user_config);
+
+    let mut handles = Vec::with_capacity(workers);
+    for _ in 0..workers {
+        let client = client.clone();
+        let config = config.clone();
+        let queue = queue.clone();
+        let failures = failures.clone();
+        let exit_code = exit_code.clone();
+        let stdout_lock = stdout_lock.clone();
+        let user_config = user_config.clone();
+        handles.push(thread::spawn(move || {
+            loop {
+                let next = { queue.lock().unwrap().next() };
+                let (index, input) = match next {
+                    Some(item) => item,
+                    None => break,
+                };
+                if let Err(err) = execute_one(&client, &config, &user_config, index, input, &stdout_lock) {
+                    failures.fetch_add(1, Ordering::SeqCst);
+                    report_error(&err, &config);
+                    // Preserve the first failure's class for the process exit code.
+                    let mut code = exit_code.lock().unwrap();
+                    if *code == 0 { *code = err.class.code(); }
+                }
+            }
+        }));
+    }
 
-        // Open up an output device for the result/response
-        let mut output = OutputDevice::new(&args.flag_output);
+    for handle in handles {
+        let _ = handle.join();
+    }
 
-        // Run the algorithm
-        let mut response = self.run_algorithm(&*args.arg_algorithm, input_args.remove(0), opts);
+    (failures.load(Ordering::SeqCst), *exit_code.lock().unwrap())
+}
 
-        // Read JSON response - scoped so that we can re-borrow response
-        let mut json_response = String::new();
-        {
-            if let Err(err) = response.read_to_string(&mut json_response) {
-                die!("Read error: {}", err)
-            };
+// Run a single input and render its response to the appropriate output device.
+fn execute_one(client: &Algorithmia, config: &RunConfig, user_config: &Config, index: usize, input: Input, stdout_lock: &Arc<Mutex<()>>) -> Result<(), RunError> {
+    let mut opts = AlgoOptions::default();
+    if config.debug { opts.enable_stdout(); }
+    if let Some(timeout) = config.timeout { opts.timeout(timeout); }
+
+    let source = input.template_path.clone();
+
+    // Read the input now (deferred until here so read failures are classified).
+    let mut data = try!(load_input(input.source, user_config));
+    if input.auto {
+        if let Some(ref input_type) = config.input_type {
+            data = coerce(data, input_type);
         }
+    }
 
-        // Handle --response and --response-body (ignoring other flags)
-        if args.flag_response || args.flag_response_body {
-            if args.flag_response {
-                let preamble = format!("{} {}\n{}", response.version, response.status, response.headers);
-                output.writeln(preamble.as_bytes());
-            };
-            output.writeln(json_response.as_bytes());
-        } else {
-            match json_response.parse::<AlgoResponse>() {
-                Ok(response) => {
-                    // Printing any API alerts
-                    if let Some(ref alerts) = response.metadata.alerts {
-                        if !args.flag_silence {
-                            for alert in alerts {
-                                stderrln!("{}", alert);
-                            }
-                        }
-                    }
+    let mut response = try!(run_algorithm(client, &config.algorithm, data, opts));
 
-                    // Printing algorithm stdout
-                    if let Some(ref stdout) = response.metadata.stdout {
-                        if args.flag_debug {
-                            print!("{}", stdout);
-                        }
-                    }
+    // Read JSON response
+    let mut json_response = String::new();
+    if let Err(err) = response.read_to_string(&mut json_response) {
+        return Err(RunError::new(ErrorClass::Io, format!("Read error: {}", err)));
+    }
 
-                    // Printing metadata
-                    if args.flag_meta || (args.flag_output.is_some() && !args.flag_silence) {
-                        println!("Completed in {:.1} seconds", response.metadata.duration);
-                    }
+    let saved = SavedResponse {
+        version: format!("{}", response.version),
+        status: format!("{}", response.status),
+        headers: format!("{}", response.headers),
+        body: json_response,
+    };
 
-                    // Smart output of result
-                    match response.result {
-                        AlgoOutput::Json(json) => output.writeln(json.to_string().as_bytes()),
-                        AlgoOutput::Text(text) => output.writeln(text.as_bytes()),
-                        AlgoOutput::Binary(bytes) => output.write(&bytes),
-                    };
-                },
-                Err(err) => die!("Response error: {}", err),
+    // Persist the full response for later replay with --read.
+    if let Some(dest) = output_path(config.write.as_ref(), source.as_ref(), index) {
+        try!(save_response(&dest, &saved).map_err(|msg| RunError::new(ErrorClass::Io, msg)));
+    }
+
+    let dest = output_path(config.output.as_ref(), source.as_ref(), index);
+    // Hold the terminal lock across the whole render when writing to STDOUT so a
+    // response and its metadata stay contiguous; file destinations are independent.
+    let _guard = if dest.is_none() { Some(stdout_lock.lock().unwrap()) } else { None };
+    let mut output = try!(OutputDevice::create(&dest)
+        .map_err(|msg| RunError::new(ErrorClass::Io, msg)));
+
+    render_response(&mut output, config, &saved)
+}
+
+// Render an already-parsed response through the --response/--response-body/smart-output logic.
+fn render_response(output: &mut OutputDevice, config: &RunConfig, saved: &SavedResponse) -> Result<(), RunError> {
+    // Handle --response and --response-body (ignoring other flags)
+    if config.response || config.response_body {
+        if config.response {
+            let preamble = format!("{} {}\n{}", saved.version, saved.status, saved.headers);
+            try!(output.writeln(preamble.as_bytes()));
+        };
+        try!(output.writeln(saved.body.as_bytes()));
+        return Ok(());
+    }
+
+    match saved.body.parse::<AlgoResponse>() {
+        Ok(response) => {
+            // Printing any API alerts
+            if let Some(ref alerts) = response.metadata.alerts {
+                if !config.silence {
+                    for alert in alerts {
+                        stderrln!("{}", alert);
+                    }
+                }
+            }
+
+            // Printing algorithm stdout
+            if let Some(ref stdout) = response.metadata.stdout {
+                if config.debug {
+                    print!("{}", stdout);
+                }
+            }
+
+            // Printing metadata
+            if config.meta || (config.output.is_some() && !config.silence) {
+                println!("Completed in {:.1} seconds", response.metadata.duration);
+            }
+
+            // Smart output of result
+            match response.result {
+                AlgoOutput::Json(json) => try!(output.writeln(json.to_string().as_bytes())),
+                AlgoOutput::Text(text) => try!(output.writeln(text.as_bytes())),
+                AlgoOutput::Binary(bytes) => try!(output.write(&bytes)),
             };
-        }
+            Ok(())
+        },
+        // A parse failure is either a genuine algorithm-runtime error returned by
+        // the API or a malformed response; tell them apart by the `error` field.
+        Err(err) => Err(RunError::new(classify_body(&saved.body), format!("Response error: {}", err))),
+    }
+}
+
+// An API error response carries an `error` object; anything else that fails to
+// parse is treated as a response-parse failure.
+fn classify_body(body: &str) -> ErrorClass {
+    match Json::from_str(body) {
+        Ok(json) => match json.find("error") {
+            Some(_) => ErrorClass::AlgorithmRuntime,
+            None => ErrorClass::ResponseParse,
+        },
+        Err(_) => ErrorClass::ResponseParse,
+    }
+}
+
+// Re-render a response previously saved with --write.
+fn replay(path: &str, config: &RunConfig) -> Result<(), RunError> {
+    let saved = try!(load_saved_response(path)
+        .map_err(|msg| RunError::new(ErrorClass::InputParse, msg)));
+    let dest = output_path(config.output.as_ref(), None, 0);
+    let mut output = try!(OutputDevice::create(&dest)
+        .map_err(|msg| RunError::new(ErrorClass::Io, msg)));
+    render_response(&mut output, config, &saved)
+}
 
+// Report a classified failure, honoring --json-errors.
+fn report_error(err: &RunError, config: &RunConfig) {
+    if config.json_errors {
+        let payload = JsonError {
+            class: err.class.name(),
+            message: &err.message,
+            algorithm: &config.algorithm,
+        };
+        match json::encode(&payload) {
+            Ok(encoded) => stderrln!("{}", encoded),
+            Err(_) => stderrln!("{}", err.message),
+        }
+    } else {
+        stderrln!("{}", err.message);
     }
 }
 
@@ -190,46 +551,53 @@ impl InputData {
     // 1. Json if it parses as JSON
     // 2. Text if it parses as UTF-8
     // 3. Fallback to binary
-    fn auto(reader: &mut Read) -> InputData {
+    fn auto(reader: &mut Read) -> Result<InputData, RunError> {
         let mut bytes: Vec<u8> = Vec::new();
-        if let Err(err) = reader.read_to_end(&mut bytes) {
-            die!("Read error: {}", err);
-        }
+        try!(reader.read_to_end(&mut bytes).map_err(read_error));
+        Ok(InputData::classify(bytes))
+    }
 
+    // Classify already-read bytes into the auto-detected variant.
+    fn classify(bytes: Vec<u8>) -> InputData {
         match String::from_utf8(bytes) {
-            Ok(data) => match Json::from_str(&data) {
-                Ok(_) => InputData::Json(data),
-                Err(_) => InputData::Text(data),
+            Ok(data) => {
+                // Only treat the input as JSON if it parses *after* comment
+                // stripping; otherwise leave arbitrary text untouched so that
+                // non-JSON --data isn't mangled by the JSONC pre-pass.
+                let stripped = strip_jsonc(&data);
+                match Json::from_str(&stripped) {
+                    Ok(_) => InputData::Json(stripped),
+                    Err(_) => InputData::Text(data),
+                }
             },
             Err(not_utf8) => InputData::Binary(not_utf8.into_bytes()),
         }
     }
 
-    fn text(reader: &mut Read) -> InputData {
+    fn text(reader: &mut Read) -> Result<InputData, RunError> {
         let mut data = String::new();
-        match reader.read_to_string(&mut data) {
-            Ok(_) => InputData::Text(data),
-            Err(err) => die!("Read error: {}", err),
-        }
+        try!(reader.read_to_string(&mut data).map_err(read_error));
+        Ok(InputData::Text(data))
     }
 
-    fn json(reader: &mut Read) -> InputData {
+    fn json(reader: &mut Read) -> Result<InputData, RunError> {
         let mut data = String::new();
-        match reader.read_to_string(&mut data) {
-            Ok(_) => InputData::Json(data),
-            Err(err) => die!("Read error: {}", err),
-        }
+        try!(reader.read_to_string(&mut data).map_err(read_error));
+        Ok(InputData::Json(strip_jsonc(&data)))
     }
 
-    fn binary(reader: &mut Read) -> InputData {
+    fn binary(reader: &mut Read) -> Result<InputData, RunError> {
         let mut bytes: Vec<u8> = Vec::new();
-        match reader.read_to_end(&mut bytes) {
-            Ok(_) => InputData::Binary(bytes),
-            Err(err) => die!("Read error: {}", err),
-        }
+        try!(reader.read_to_end(&mut bytes).map_err(read_error));
+        Ok(InputData::Binary(bytes))
     }
 }
 
+// A failed read of an input source, mapped to the `io` error class.
+fn read_error(err: io::Error) -> RunError {
+    RunError::new(ErrorClass::Io, format!("Read error: {}", err))
+}
+
 
 // The device specified by --output flag
 // Only the result or response is written to this device
@@ -238,62 +606,344 @@ struct OutputDevice {
 }
 
 impl OutputDevice {
-    fn new(output_dest: &Option<String>) -> OutputDevice {
+    // Open the device for an --output destination, returning an error rather than
+    // aborting so that one unwritable target doesn't take down a whole batch.
+    fn create(output_dest: &Option<String>) -> Result<OutputDevice, String> {
         match output_dest {
             &Some(ref file_path) => match File::create(file_path) {
-                Ok(buf) => OutputDevice{ writer: Box::new(buf) },
-                Err(err) => die!("Unable to create file: {}", err),
+                Ok(buf) => Ok(OutputDevice{ writer: Box::new(buf) }),
+                Err(err) => Err(format!("Unable to create file '{}': {}", file_path, err)),
             },
-            &None => OutputDevice{ writer: Box::new(io::stdout()) },
+            &None => Ok(OutputDevice{ writer: Box::new(io::stdout()) }),
         }
     }
 
-    fn write(&mut self, bytes: &[u8]) {
-        match self.writer.write(bytes) {
-            Ok(_) => (),
-            Err(err) => die!("Error writing output: {}", err),
-        }
+    // Return write failures rather than aborting so a mid-render error (disk
+    // full, closed pipe) is counted as one input's failure instead of killing
+    // the whole batch.
+    fn write(&mut self, bytes: &[u8]) -> Result<(), RunError> {
+        self.writer.write_all(bytes)
+            .map_err(|err| RunError::new(ErrorClass::Io, format!("Error writing output: {}", err)))
     }
 
-    fn writeln(&mut self, bytes: &[u8]) {
-        self.write(bytes);
-        self.write(b"\n");
+    fn writeln(&mut self, bytes: &[u8]) -> Result<(), RunError> {
+        try!(self.write(bytes));
+        self.write(b"\n")
     }
 }
 
 impl Run {
     pub fn new(client: Algorithmia) -> Self { Run{ client:client } }
+}
 
-    fn run_algorithm(&self, algo: &str, input_data: InputData, opts: AlgoOptions) -> Response {
-        let mut algorithm = self.client.algo(algo);
-        let algorithm = algorithm.set_options(opts);
 
-        let result = match input_data {
-            InputData::Text(text) => algorithm.pipe_as(&*text, Mime(TopLevel::Text, SubLevel::Plain, vec![])),
-            InputData::Json(json) => algorithm.pipe_as(&*json, Mime(TopLevel::Application, SubLevel::Json, vec![])),
-            InputData::Binary(bytes) => algorithm.pipe_as(&*bytes, Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![])),
-        };
+// Run the algorithm over a single input, returning the raw HTTP response.
+fn run_algorithm(client: &Algorithmia, algo: &str, input_data: InputData, opts: AlgoOptions) -> Result<Response, RunError> {
+    let mut algorithm = client.algo(algo);
+    let algorithm = algorithm.set_options(opts);
+
+    let result = match input_data {
+        InputData::Text(text) => algorithm.pipe_as(&*text, Mime(TopLevel::Text, SubLevel::Plain, vec![])),
+        InputData::Json(json) => algorithm.pipe_as(&*json, Mime(TopLevel::Application, SubLevel::Json, vec![])),
+        InputData::Binary(bytes) => algorithm.pipe_as(&*bytes, Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![])),
+    };
+
+    result.map_err(|err| RunError::new(ErrorClass::Transport, format!("Error calling algorithm: {}", err)))
+}
+
+
+// Persist a parsed response to disk as pretty-printed JSON.
+fn save_response(dest: &str, saved: &SavedResponse) -> Result<(), String> {
+    let encoded = format!("{}", json::as_pretty_json(saved));
+    File::create(dest)
+        .and_then(|mut f| f.write_all(encoded.as_bytes()))
+        .map_err(|err| format!("Unable to write response '{}': {}", dest, err))
+}
+
+// Reconstruct a response previously written with --write.
+fn load_saved_response(path: &str) -> Result<SavedResponse, String> {
+    let mut raw = String::new();
+    try!(File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut raw))
+        .map_err(|err| format!("Unable to read response '{}': {}", path, err)));
+    json::decode(&raw).map_err(|err| format!("Invalid saved response '{}': {}", path, err))
+}
+
+// Pin the algorithm version from config when the user didn't specify one
+// (an explicit USERNAME/ALGONAME/VERSION on the command line always wins).
+fn resolve_algorithm(algorithm: &str, algo_config: &AlgoConfig) -> String {
+    if algorithm.matches('/').count() >= 2 {
+        return algorithm.to_owned();
+    }
+    match algo_config.version {
+        Some(ref version) => format!("{}/{}", algorithm, version),
+        None => algorithm.to_owned(),
+    }
+}
+
+// Resolve a not-yet-read input to its bytes, reading files (and running any
+// matching preprocessing adapter) lazily so read failures become RunErrors.
+fn load_input(source: InputSource, user_config: &Config) -> Result<InputData, RunError> {
+    match source {
+        InputSource::Inline(data) => Ok(data),
+        InputSource::File { path, kind } => build_file_input(&path, kind, user_config),
+    }
+}
+
+// Read a -D/-J/-T/-B file source, routing -D through a matching preprocessing
+// adapter if one is configured and otherwise interpreting it per FileKind.
+fn build_file_input(path: &str, kind: FileKind, user_config: &Config) -> Result<InputData, RunError> {
+    match kind {
+        FileKind::Auto => {
+            if path != "-" {
+                if let Some(adapter) = user_config.adapter_for(path) {
+                    return run_adapter(path, adapter);
+                }
+            }
+            InputData::auto(&mut try!(get_src(path)))
+        },
+        FileKind::Json => InputData::json(&mut try!(get_src(path))),
+        FileKind::Text => InputData::text(&mut try!(get_src(path))),
+        FileKind::Binary => InputData::binary(&mut try!(get_src(path))),
+    }
+}
 
-        match result {
-            Ok(response) => response,
-            Err(err) => die!("Error calling algorithm: {}", err),
+// Stream a file through an external adapter command and wrap its STDOUT as the
+// configured input type.
+fn run_adapter(src: &str, adapter: &Adapter) -> Result<InputData, RunError> {
+    let output = try!(pipe_through(src, &adapter.command).map_err(|err|
+        RunError::new(ErrorClass::Io, format!("Adapter '{}' for {} failed: {}", adapter.command, src, err))));
+
+    match &*adapter.input_type {
+        "json" => match String::from_utf8(output) {
+            Ok(data) => Ok(InputData::Json(strip_jsonc(&data))),
+            Err(_) => Err(RunError::new(ErrorClass::InputParse,
+                format!("Adapter '{}' produced non-UTF-8 JSON for {}", adapter.command, src))),
+        },
+        "text" => match String::from_utf8(output) {
+            Ok(data) => Ok(InputData::Text(data)),
+            Err(_) => Err(RunError::new(ErrorClass::InputParse,
+                format!("Adapter '{}' produced non-UTF-8 text for {}", adapter.command, src))),
+        },
+        "binary" => Ok(InputData::Binary(output)),
+        _ => InputData::auto(&mut &output[..]),
+    }
+}
+
+// Run `sh -c <command>` with the file contents on STDIN, returning its STDOUT.
+fn pipe_through(src: &str, command: &str) -> Result<Vec<u8>, String> {
+    let mut input: Vec<u8> = Vec::new();
+    let mut reader = try!(get_src(src).map_err(|err| err.message));
+    try!(reader.read_to_end(&mut input).map_err(|err| format!("read error: {}", err)));
+
+    let mut child = try!(Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("unable to spawn command: {}", err)));
+
+    // Feed STDIN from a separate thread so an adapter that streams output as it
+    // reads (e.g. `convert - png:-`) can't deadlock: otherwise the child blocks
+    // writing into a full STDOUT pipe while we block writing STDIN, and neither
+    // side drains the other once the input exceeds the ~64KB pipe buffer.
+    let mut stdin = try!(child.stdin.take().ok_or_else(|| "unable to open command stdin".to_owned()));
+    let writer = thread::spawn(move || stdin.write_all(&input));
+
+    let output = try!(child.wait_with_output().map_err(|err| format!("command failed: {}", err)));
+    if !output.status.success() {
+        return Err(format!("command exited with {}", output.status));
+    }
+    // A child that consumes only part of its input and exits cleanly closes the
+    // pipe early, so a broken-pipe write is only a failure when the command did.
+    match writer.join() {
+        Ok(Ok(())) => {},
+        Ok(Err(ref err)) if err.kind() == io::ErrorKind::BrokenPipe => {},
+        Ok(Err(err)) => return Err(format!("unable to write to command: {}", err)),
+        Err(_) => return Err("command stdin writer panicked".to_owned()),
+    }
+    Ok(output.stdout)
+}
+
+// Re-interpret an auto-detected input according to a configured default type.
+fn coerce(data: InputData, input_type: &str) -> InputData {
+    match input_type {
+        "json" => match data {
+            InputData::Text(s) | InputData::Json(s) => InputData::Json(strip_jsonc(&s)),
+            other => other,
+        },
+        "text" => match data {
+            InputData::Text(s) | InputData::Json(s) => InputData::Text(s),
+            other => other,
+        },
+        "binary" => match data {
+            InputData::Text(s) | InputData::Json(s) => InputData::Binary(s.into_bytes()),
+            other => other,
+        },
+        _ => data,
+    }
+}
+
+// Expand a file-input source into concrete paths. STDIN ('-') is passed through
+// untouched; anything else is treated as a glob pattern. A pattern that matches
+// nothing falls back to the literal string so a simple typo still surfaces the
+// usual file-open error.
+fn expand_sources(pattern: &str) -> Vec<String> {
+    if pattern == "-" {
+        return vec!["-".to_owned()];
+    }
+
+    match glob(pattern) {
+        Ok(paths) => {
+            let matches: Vec<String> = paths.filter_map(|entry| match entry {
+                Ok(path) => Some(path.to_string_lossy().into_owned()),
+                Err(err) => { stderrln!("Error reading path: {}", err); None },
+            }).collect();
+            if matches.is_empty() { vec![pattern.to_owned()] } else { matches }
+        },
+        Err(_) => vec![pattern.to_owned()],
+    }
+}
+
+// Warn when a multi-input batch is pointed at a fixed path with no template
+// tokens, since every input would otherwise overwrite the same destination.
+fn warn_single_target(flag: &str, template: Option<&String>) {
+    if let Some(path) = template {
+        if !path.contains('{') {
+            stderrln!("Warning: {} '{}' has no template tokens; every input in the batch will overwrite it", flag, path);
         }
     }
 }
 
+// Resolve the concrete output path for one input, expanding any --output template
+// tokens against the input's source file. A template without tokens is used
+// verbatim (the common single-input case); None means write to STDOUT.
+fn output_path(template: Option<&String>, source: Option<&String>, index: usize) -> Option<String> {
+    let template = match template {
+        Some(t) => t,
+        None => return None,
+    };
+
+    if !template.contains('{') {
+        return Some(template.clone());
+    }
+
+    let (basename, filename, ext) = match source {
+        Some(path) => {
+            let path = Path::new(path);
+            (
+                path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+                path.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+                path.extension().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+            )
+        },
+        None => {
+            let name = format!("input{}", index);
+            (name.clone(), name, String::new())
+        },
+    };
 
-fn get_src(src: &str) -> Box<Read> {
+    Some(template
+        .replace("{basename}", &basename)
+        .replace("{filename}", &filename)
+        .replace("{ext}", &ext)
+        .replace("{index}", &index.to_string()))
+}
+
+
+// Strip JSON-with-comments (JSONC) extensions down to canonical JSON: remove
+// `//` line comments, `/* */` block comments, and trailing commas before `}`/`]`.
+// Comment-like sequences and commas inside string literals are preserved. The
+// returned string is what gets parsed and sent as application/json.
+pub fn strip_jsonc(input: &str) -> String {
+    strip_trailing_commas(&strip_comments(input))
+}
+
+fn strip_comments(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if in_string {
+            out.push(c);
+            if escaped { escaped = false; }
+            else if c == b'\\' { escaped = true; }
+            else if c == b'"' { in_string = false; }
+            i += 1;
+            continue;
+        }
+        if c == b'"' {
+            in_string = true;
+            out.push(c);
+            i += 1;
+        } else if c == b'/' && i + 1 < bytes.len() && bytes[i + 1] == b'/' {
+            i += 2;
+            while i < bytes.len() && bytes[i] != b'\n' { i += 1; }
+        } else if c == b'/' && i + 1 < bytes.len() && bytes[i + 1] == b'*' {
+            i += 2;
+            while i + 1 < bytes.len() && !(bytes[i] == b'*' && bytes[i + 1] == b'/') { i += 1; }
+            i += 2;
+        } else {
+            out.push(c);
+            i += 1;
+        }
+    }
+    // Safe: a dropped run of bytes always starts at an ASCII `/` and ends before an
+    // ASCII newline, after `*/`, or at the end of input, so every retained
+    // multi-byte UTF-8 sequence is copied intact.
+    String::from_utf8(out).unwrap()
+}
+
+fn strip_trailing_commas(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if in_string {
+            out.push(c);
+            if escaped { escaped = false; }
+            else if c == b'\\' { escaped = true; }
+            else if c == b'"' { in_string = false; }
+            i += 1;
+            continue;
+        }
+        if c == b'"' {
+            in_string = true;
+            out.push(c);
+            i += 1;
+            continue;
+        }
+        if c == b',' {
+            let mut j = i + 1;
+            while j < bytes.len() && (bytes[j] as char).is_whitespace() { j += 1; }
+            if j < bytes.len() && (bytes[j] == b'}' || bytes[j] == b']') {
+                i += 1;
+                continue;
+            }
+        }
+        out.push(c);
+        i += 1;
+    }
+    String::from_utf8(out).unwrap()
+}
+
+fn get_src(src: &str) -> Result<Box<Read>, RunError> {
     match src {
-        "-" => Box::new(io::stdin()) as Box<Read>,
+        "-" => Ok(Box::new(io::stdin()) as Box<Read>),
         s => open_file(Path::new(&s)),
     }
 }
 
-fn open_file(path: &Path) -> Box<Read> {
-    let display = path.display();
-    let file = match File::open(&path) {
-        Err(err) => die!("Error opening {}: {}", display, err),
-        Ok(file) => file,
-    };
-    Box::new(file)
+fn open_file(path: &Path) -> Result<Box<Read>, RunError> {
+    match File::open(&path) {
+        Ok(file) => Ok(Box::new(file) as Box<Read>),
+        Err(err) => Err(RunError::new(ErrorClass::Io, format!("Error opening {}: {}", path.display(), err))),
+    }
 }